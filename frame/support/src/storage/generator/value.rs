@@ -14,14 +14,72 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
-use codec::{FullCodec, Encode, EncodeLike, Decode};
+use codec::{FullCodec, Encode, EncodeLike, Decode, Compact};
+use sp_std::vec::Vec;
 use crate::{
 	Never,
 	storage::{self, unhashed, StorageAppend},
 	hash::{Twox128, StorageHasher},
-	traits::Len
+	traits::{Len, Get}
 };
 
+/// Maps a storage value's raw `Option<Value>` to and from its `Query` type.
+/// `OptionQuery` and `ValueQuery` are the two instantiations.
+pub trait QueryKindTrait<Value, OnEmpty> {
+	/// The type that get/take returns.
+	type Query: FullCodec;
+
+	/// Convert an optional value retrieved from storage to the type queried.
+	fn from_optional_value_to_query(v: Option<Value>) -> Self::Query;
+
+	/// Convert a query to an optional value into storage.
+	fn from_query_to_optional_value(v: Self::Query) -> Option<Value>;
+}
+
+/// `Query` is `Option<Value>`; an absent entry is reported as `None` rather
+/// than being defaulted.
+pub struct OptionQuery;
+
+impl<Value: FullCodec, OnEmpty> QueryKindTrait<Value, OnEmpty> for OptionQuery {
+	type Query = Option<Value>;
+
+	fn from_optional_value_to_query(v: Option<Value>) -> Self::Query {
+		v
+	}
+
+	fn from_query_to_optional_value(v: Self::Query) -> Option<Value> {
+		v
+	}
+}
+
+/// `Query` is `Value`; an absent entry is defaulted to `OnEmpty::get()`.
+pub struct ValueQuery;
+
+impl<Value: FullCodec, OnEmpty: Get<Value>> QueryKindTrait<Value, OnEmpty> for ValueQuery {
+	type Query = Value;
+
+	fn from_optional_value_to_query(v: Option<Value>) -> Self::Query {
+		v.unwrap_or_else(OnEmpty::get)
+	}
+
+	fn from_query_to_optional_value(v: Self::Query) -> Option<Value> {
+		Some(v)
+	}
+}
+
+/// Decode the SCALE compact length prefix of an encoded list, returning the
+/// item count together with the remaining bytes (the encoded items themselves).
+///
+/// Used by `append_multi`/`append_or_put` to splice new items onto an existing
+/// encoded list without decoding the items themselves.
+fn decode_compact_len(data: &[u8]) -> Result<(u32, &[u8]), &'static str> {
+	let mut input = data;
+	let len = <Compact<u32> as Decode>::decode(&mut input)
+		.map_err(|_| "StorageValue::append_multi: could not decode length prefix")?
+		.0;
+	Ok((len, input))
+}
+
 /// Generator for `StorageValue` used by `decl_storage`.
 ///
 /// By default value is stored at:
@@ -32,6 +90,12 @@ pub trait StorageValue<T: FullCodec> {
 	/// The type that get/take returns.
 	type Query;
 
+	/// `OptionQuery` or `ValueQuery`; backs the two methods below.
+	type QueryKind: QueryKindTrait<T, Self::OnEmpty, Query = Self::Query>;
+
+	/// Default used for `Self::Query` when empty, for `QueryKind = ValueQuery`.
+	type OnEmpty;
+
 	/// Module prefix. Used for generating final key.
 	fn module_prefix() -> &'static [u8];
 
@@ -39,42 +103,78 @@ pub trait StorageValue<T: FullCodec> {
 	fn storage_prefix() -> &'static [u8];
 
 	/// Convert an optional value retrieved from storage to the type queried.
-	fn from_optional_value_to_query(v: Option<T>) -> Self::Query;
+	fn from_optional_value_to_query(v: Option<T>) -> Self::Query {
+		Self::QueryKind::from_optional_value_to_query(v)
+	}
 
 	/// Convert a query to an optional value into storage.
-	fn from_query_to_optional_value(v: Self::Query) -> Option<T>;
+	fn from_query_to_optional_value(v: Self::Query) -> Option<T> {
+		Self::QueryKind::from_query_to_optional_value(v)
+	}
 
 	/// Generate the full key used in top storage.
+	///
+	/// Prefer `storage_value_final_key_cached()` on hot paths; this one always
+	/// recomputes both `Twox128` hashes.
 	fn storage_value_final_key() -> [u8; 32] {
 		let mut final_key = [0u8; 32];
 		final_key[0..16].copy_from_slice(&Twox128::hash(Self::module_prefix()));
 		final_key[16..32].copy_from_slice(&Twox128::hash(Self::storage_prefix()));
 		final_key
 	}
+
+	/// Same key as `storage_value_final_key()`, computed once per concrete
+	/// `Self` and cached thereafter.
+	///
+	/// The `static` below is monomorphized per `Self`, so each storage item
+	/// gets its own cache slot. Races over the `ready` flag are benign: every
+	/// racing caller computes and stores the same deterministic value.
+	fn storage_value_final_key_cached() -> [u8; 32] {
+		use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+		static READY: AtomicBool = AtomicBool::new(false);
+		static WORDS: [AtomicU64; 4] = [
+			AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),
+		];
+
+		if !READY.load(Ordering::Acquire) {
+			let key = Self::storage_value_final_key();
+			for (word, bytes) in WORDS.iter().zip(key.chunks_exact(8)) {
+				word.store(u64::from_le_bytes(bytes.try_into().expect("chunk is 8 bytes")), Ordering::Relaxed);
+			}
+			READY.store(true, Ordering::Release);
+		}
+
+		let mut key = [0u8; 32];
+		for (bytes, word) in key.chunks_exact_mut(8).zip(WORDS.iter()) {
+			bytes.copy_from_slice(&word.load(Ordering::Relaxed).to_le_bytes());
+		}
+		key
+	}
 }
 
 impl<T: FullCodec, G: StorageValue<T>> storage::StorageValue<T> for G {
 	type Query = G::Query;
 
 	fn hashed_key() -> [u8; 32] {
-		Self::storage_value_final_key()
+		Self::storage_value_final_key_cached()
 	}
 
 	fn exists() -> bool {
-		unhashed::exists(&Self::storage_value_final_key())
+		unhashed::exists(&Self::storage_value_final_key_cached())
 	}
 
 	fn get() -> Self::Query {
-		let value = unhashed::get(&Self::storage_value_final_key());
+		let value = unhashed::get(&Self::storage_value_final_key_cached());
 		G::from_optional_value_to_query(value)
 	}
 
 	fn try_get() -> Result<T, ()> {
-		unhashed::get(&Self::storage_value_final_key()).ok_or(())
+		unhashed::get(&Self::storage_value_final_key_cached()).ok_or(())
 	}
 
 	fn translate<O: Decode, F: FnOnce(Option<O>) -> Option<T>>(f: F) -> Result<Option<T>, ()> {
-		let key = Self::storage_value_final_key();
+		let key = Self::storage_value_final_key_cached();
 
 		// attempt to get the length directly.
 		let maybe_old = match unhashed::get_raw(&key) {
@@ -91,19 +191,19 @@ impl<T: FullCodec, G: StorageValue<T>> storage::StorageValue<T> for G {
 	}
 
 	fn put<Arg: EncodeLike<T>>(val: Arg) {
-		unhashed::put(&Self::storage_value_final_key(), &val)
+		unhashed::put(&Self::storage_value_final_key_cached(), &val)
 	}
 
 	fn set(maybe_val: Self::Query) {
 		if let Some(val) = G::from_query_to_optional_value(maybe_val) {
-			unhashed::put(&Self::storage_value_final_key(), &val)
+			unhashed::put(&Self::storage_value_final_key_cached(), &val)
 		} else {
-			unhashed::kill(&Self::storage_value_final_key())
+			unhashed::kill(&Self::storage_value_final_key_cached())
 		}
 	}
 
 	fn kill() {
-		unhashed::kill(&Self::storage_value_final_key())
+		unhashed::kill(&Self::storage_value_final_key_cached())
 	}
 
 	fn mutate<R, F: FnOnce(&mut G::Query) -> R>(f: F) -> R {
@@ -123,8 +223,43 @@ impl<T: FullCodec, G: StorageValue<T>> storage::StorageValue<T> for G {
 		ret
 	}
 
+	fn mutate_exists<R, F: FnOnce(&mut Option<T>) -> R>(f: F) -> R {
+		Self::try_mutate_exists(|v| Ok::<R, Never>(f(v))).expect("`Never` can not be constructed; qed")
+	}
+
+	fn try_mutate_exists<R, E, F: FnOnce(&mut Option<T>) -> Result<R, E>>(f: F) -> Result<R, E> {
+		let key = Self::storage_value_final_key_cached();
+		let mut val = unhashed::get(&key);
+
+		let ret = f(&mut val);
+		if ret.is_ok() {
+			match val {
+				Some(ref val) => unhashed::put(&key, val),
+				None => unhashed::kill(&key),
+			}
+		}
+		ret
+	}
+
+	fn swap<Other: storage::StorageValue<T>>() {
+		let key = Self::storage_value_final_key_cached();
+		let other_key = Other::hashed_key();
+
+		let this = unhashed::get_raw(&key);
+		let other = unhashed::get_raw(&other_key);
+
+		match other {
+			Some(other) => unhashed::put_raw(&key, &other),
+			None => unhashed::kill(&key),
+		}
+		match this {
+			Some(this) => unhashed::put_raw(&other_key, &this),
+			None => unhashed::kill(&other_key),
+		}
+	}
+
 	fn take() -> G::Query {
-		let key = Self::storage_value_final_key();
+		let key = Self::storage_value_final_key_cached();
 		let value = unhashed::get(&key);
 		if value.is_some() {
 			unhashed::kill(&key)
@@ -138,12 +273,70 @@ impl<T: FullCodec, G: StorageValue<T>> storage::StorageValue<T> for G {
 		EncodeLikeItem: EncodeLike<Item>,
 		T: StorageAppend<Item>,
 	{
-		let key = Self::storage_value_final_key();
+		let key = Self::storage_value_final_key_cached();
 		sp_io::storage::append(&key, item.encode());
 	}
 
+	fn append_multi<Items, Item, EncodeLikeItem>(items: Items) -> Result<(), &'static str>
+	where
+		Item: Encode,
+		EncodeLikeItem: EncodeLike<Item>,
+		Items: IntoIterator<Item = EncodeLikeItem>,
+		Items::IntoIter: ExactSizeIterator,
+		T: StorageAppend<Item>,
+	{
+		let items = items.into_iter();
+		let added = items.len();
+		if added == 0 {
+			return Ok(());
+		}
+
+		let key = Self::storage_value_final_key_cached();
+		let existing = unhashed::get_raw(&key);
+		let (old_len, old_items) = match existing.as_deref() {
+			Some(data) => decode_compact_len(data)?,
+			None => (0, &[][..]),
+		};
+		if added > u32::MAX as usize {
+			return Err("StorageValue::append_multi: item count overflowed");
+		}
+		let new_len = old_len.checked_add(added as u32)
+			.ok_or("StorageValue::append_multi: item count overflowed")?;
+
+		let mut encoded = Vec::with_capacity(old_items.len() + items.len() * 32);
+		Compact(new_len).encode_to(&mut encoded);
+		encoded.extend_from_slice(old_items);
+		for item in items {
+			item.encode_to(&mut encoded);
+		}
+
+		unhashed::put_raw(&key, &encoded);
+		Ok(())
+	}
+
+	fn append_or_put<Items, Item, EncodeLikeItem>(items: Items)
+	where
+		Item: Encode,
+		EncodeLikeItem: EncodeLike<Item>,
+		Items: IntoIterator<Item = EncodeLikeItem> + EncodeLike<T>,
+		Items::IntoIter: ExactSizeIterator,
+		T: StorageAppend<Item>,
+	{
+		let key = Self::storage_value_final_key_cached();
+		let can_extend = unhashed::get_raw(&key)
+			.map(|data| decode_compact_len(&data).is_ok())
+			.unwrap_or(true);
+
+		if can_extend {
+			Self::append_multi::<Items, Item, EncodeLikeItem>(items)
+				.expect("existing value decodes as a list; only the item count can overflow u32");
+		} else {
+			Self::put(items);
+		}
+	}
+
 	fn decode_len() -> Result<usize, &'static str> where T: codec::DecodeLength, T: Len {
-		let key = Self::storage_value_final_key();
+		let key = Self::storage_value_final_key_cached();
 
 		// attempt to get the length directly.
 		if let Some(k) = unhashed::get_raw(&key) {
@@ -157,3 +350,313 @@ impl<T: FullCodec, G: StorageValue<T>> storage::StorageValue<T> for G {
 		}
 	}
 }
+
+/// Final key for a [`VersionedStorageValue`]'s on-chain schema version.
+fn storage_version_final_key<T: FullCodec, G: StorageValue<T>>() -> [u8; 32] {
+	let mut final_key = [0u8; 32];
+	final_key[0..16].copy_from_slice(&Twox128::hash(G::module_prefix()));
+	final_key[16..32].copy_from_slice(&Twox128::hash(&[G::storage_prefix(), b":version" as &[u8]].concat()));
+	final_key
+}
+
+/// Drives a [`StorageValue`] through `translate`-based upgrade steps, tracking
+/// progress with a small on-chain version number.
+pub trait VersionedStorageValue<T: FullCodec>: StorageValue<T> {
+	/// The schema version currently recorded on-chain, or `0` if unset.
+	fn on_chain_version() -> u16 {
+		unhashed::get(&storage_version_final_key::<T, Self>()).unwrap_or(0)
+	}
+
+	/// Apply at most one hop of `steps` towards `current_code_version`.
+	///
+	/// All of `steps` share the same `Prev` type, but storage only ever holds
+	/// one encoding at a time: once a step's `translate` succeeds, the stored
+	/// bytes are `T`-encoded, not `Prev`-encoded, so a second step in the same
+	/// call would try to decode already-migrated data as `Prev`. `migrate`
+	/// therefore applies the single lowest-numbered owed step and returns;
+	/// callers more than one version behind call it again per hop, passing
+	/// the `Prev` type recorded for that hop.
+	fn migrate<Prev: Decode>(
+		current_code_version: u16,
+		steps: &[(u16, fn(Option<Prev>) -> Option<T>)],
+	) -> Result<(), ()> {
+		let version = Self::on_chain_version();
+		if version >= current_code_version {
+			return Ok(());
+		}
+
+		let next = steps.iter()
+			.filter(|(v, _)| *v > version && *v <= current_code_version)
+			.min_by_key(|(v, _)| *v);
+		if let Some((step_version, step)) = next {
+			<Self as storage::StorageValue<T>>::translate(*step)?;
+			unhashed::put(&storage_version_final_key::<T, Self>(), step_version);
+		}
+
+		Ok(())
+	}
+}
+
+impl<T: FullCodec, G: StorageValue<T>> VersionedStorageValue<T> for G {}
+
+#[cfg(test)]
+mod tests {
+	use sp_io::TestExternalities;
+	use super::*;
+
+	struct ValueA;
+	impl StorageValue<u32> for ValueA {
+		type Query = Option<u32>;
+		type QueryKind = OptionQuery;
+		type OnEmpty = ();
+		fn module_prefix() -> &'static [u8] { b"Test" }
+		fn storage_prefix() -> &'static [u8] { b"ValueA" }
+	}
+
+	struct ValueB;
+	impl StorageValue<u32> for ValueB {
+		type Query = Option<u32>;
+		type QueryKind = OptionQuery;
+		type OnEmpty = ();
+		fn module_prefix() -> &'static [u8] { b"Test" }
+		fn storage_prefix() -> &'static [u8] { b"ValueB" }
+	}
+
+	#[test]
+	fn final_key_cached_matches_uncached_and_is_stable() {
+		let uncached = ValueA::storage_value_final_key();
+
+		assert_eq!(ValueA::storage_value_final_key_cached(), uncached);
+		// Repeated calls reuse the cache rather than recomputing; the result
+		// must still agree with the uncached computation every time.
+		assert_eq!(ValueA::storage_value_final_key_cached(), uncached);
+		assert_eq!(ValueA::storage_value_final_key_cached(), uncached);
+
+		// A different `Self` gets its own cache slot and its own key.
+		assert_eq!(ValueB::storage_value_final_key_cached(), ValueB::storage_value_final_key());
+		assert_ne!(ValueB::storage_value_final_key_cached(), uncached);
+	}
+
+	#[test]
+	fn mutate_exists_creates_and_deletes_entry() {
+		TestExternalities::default().execute_with(|| {
+			assert_eq!(<ValueA as storage::StorageValue<u32>>::get(), None);
+
+			<ValueA as storage::StorageValue<u32>>::mutate_exists(|v| {
+				assert!(v.is_none());
+				*v = Some(1);
+			});
+			assert_eq!(<ValueA as storage::StorageValue<u32>>::get(), Some(1));
+
+			<ValueA as storage::StorageValue<u32>>::mutate_exists(|v| *v = None);
+			assert_eq!(<ValueA as storage::StorageValue<u32>>::get(), None);
+			assert!(!<ValueA as storage::StorageValue<u32>>::exists());
+		});
+	}
+
+	#[test]
+	fn try_mutate_exists_rolls_back_on_err() {
+		TestExternalities::default().execute_with(|| {
+			<ValueA as storage::StorageValue<u32>>::put(1u32);
+
+			let res: Result<(), ()> = <ValueA as storage::StorageValue<u32>>::try_mutate_exists(|v| {
+				*v = Some(2);
+				Err(())
+			});
+
+			assert!(res.is_err());
+			assert_eq!(<ValueA as storage::StorageValue<u32>>::get(), Some(1));
+		});
+	}
+
+	#[test]
+	fn swap_exchanges_values_including_absent_side() {
+		TestExternalities::default().execute_with(|| {
+			<ValueA as storage::StorageValue<u32>>::put(1u32);
+			assert_eq!(<ValueB as storage::StorageValue<u32>>::get(), None);
+
+			<ValueA as storage::StorageValue<u32>>::swap::<ValueB>();
+
+			assert_eq!(<ValueA as storage::StorageValue<u32>>::get(), None);
+			assert_eq!(<ValueB as storage::StorageValue<u32>>::get(), Some(1));
+		});
+	}
+
+	struct DefaultAnswer;
+	impl Get<Vec<u32>> for DefaultAnswer {
+		fn get() -> Vec<u32> { vec![4, 2] }
+	}
+
+	struct WithDefault;
+	impl StorageValue<Vec<u32>> for WithDefault {
+		type Query = Vec<u32>;
+		type QueryKind = ValueQuery;
+		type OnEmpty = DefaultAnswer;
+		fn module_prefix() -> &'static [u8] { b"Test" }
+		fn storage_prefix() -> &'static [u8] { b"WithDefault" }
+	}
+
+	#[test]
+	fn value_query_falls_back_to_on_empty() {
+		TestExternalities::default().execute_with(|| {
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::get(), vec![4, 2]);
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::decode_len().unwrap(), 2);
+
+			<WithDefault as storage::StorageValue<Vec<u32>>>::put(vec![1, 2, 3]);
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::get(), vec![1, 2, 3]);
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::decode_len().unwrap(), 3);
+
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::take(), vec![1, 2, 3]);
+			assert_eq!(<WithDefault as storage::StorageValue<Vec<u32>>>::get(), vec![4, 2]);
+		});
+	}
+
+	struct Log;
+	impl StorageValue<Vec<u32>> for Log {
+		type Query = Option<Vec<u32>>;
+		type QueryKind = OptionQuery;
+		type OnEmpty = ();
+		fn module_prefix() -> &'static [u8] { b"Test" }
+		fn storage_prefix() -> &'static [u8] { b"Log" }
+	}
+
+	#[test]
+	fn append_multi_extends_existing_list() {
+		TestExternalities::default().execute_with(|| {
+			<Log as storage::StorageValue<Vec<u32>>>::append_multi::<Vec<u32>, u32, u32>(vec![1, 2])
+				.unwrap();
+			<Log as storage::StorageValue<Vec<u32>>>::append_multi::<Vec<u32>, u32, u32>(vec![3])
+				.unwrap();
+
+			assert_eq!(<Log as storage::StorageValue<Vec<u32>>>::get(), Some(vec![1, 2, 3]));
+		});
+	}
+
+	#[test]
+	fn append_multi_is_a_noop_for_empty_input() {
+		TestExternalities::default().execute_with(|| {
+			<Log as storage::StorageValue<Vec<u32>>>::append_multi::<Vec<u32>, u32, u32>(Vec::new())
+				.unwrap();
+
+			assert_eq!(<Log as storage::StorageValue<Vec<u32>>>::get(), None);
+		});
+	}
+
+	#[test]
+	fn append_multi_detects_item_count_overflow() {
+		TestExternalities::default().execute_with(|| {
+			let mut encoded = Vec::new();
+			Compact(u32::MAX).encode_to(&mut encoded);
+			unhashed::put_raw(&Log::storage_value_final_key(), &encoded);
+
+			let result = <Log as storage::StorageValue<Vec<u32>>>::append_multi::<Vec<u32>, u32, u32>(
+				vec![1],
+			);
+
+			assert_eq!(result, Err("StorageValue::append_multi: item count overflowed"));
+		});
+	}
+
+	#[test]
+	fn append_or_put_falls_back_when_existing_value_is_not_a_list() {
+		TestExternalities::default().execute_with(|| {
+			// A lone `0xff` starts a "big integer" compact mode demanding more
+			// length bytes than are present, so it can't decode as a length prefix.
+			unhashed::put_raw(&Log::storage_value_final_key(), &[0xff]);
+
+			<Log as storage::StorageValue<Vec<u32>>>::append_or_put::<Vec<u32>, u32, u32>(vec![7, 8]);
+
+			assert_eq!(<Log as storage::StorageValue<Vec<u32>>>::get(), Some(vec![7, 8]));
+		});
+	}
+
+	struct Versioned;
+	impl StorageValue<u64> for Versioned {
+		type Query = Option<u64>;
+		type QueryKind = OptionQuery;
+		type OnEmpty = ();
+		fn module_prefix() -> &'static [u8] { b"Test" }
+		fn storage_prefix() -> &'static [u8] { b"Versioned" }
+	}
+
+	#[test]
+	fn migrate_is_noop_when_already_current() {
+		TestExternalities::default().execute_with(|| {
+			<Versioned as storage::StorageValue<u64>>::put(7u64);
+
+			let steps: &[(u16, fn(Option<u64>) -> Option<u64>)] = &[(1, |v| v)];
+			Versioned::migrate(0, steps).unwrap();
+
+			assert_eq!(<Versioned as storage::StorageValue<u64>>::get(), Some(7));
+			assert_eq!(Versioned::on_chain_version(), 0);
+		});
+	}
+
+	#[test]
+	fn migrate_applies_one_hop_and_advances_version() {
+		TestExternalities::default().execute_with(|| {
+			// As if written by an older runtime that stored a plain `u32`.
+			unhashed::put(&Versioned::storage_value_final_key(), &11u32);
+
+			let steps: &[(u16, fn(Option<u32>) -> Option<u64>)] =
+				&[(1, |v: Option<u32>| v.map(|v| v as u64 + 1))];
+			Versioned::migrate(1, steps).unwrap();
+
+			assert_eq!(<Versioned as storage::StorageValue<u64>>::get(), Some(12));
+			assert_eq!(Versioned::on_chain_version(), 1);
+		});
+	}
+
+	#[test]
+	fn migrate_only_applies_the_next_owed_hop() {
+		TestExternalities::default().execute_with(|| {
+			unhashed::put(&Versioned::storage_value_final_key(), &11u32);
+
+			let steps: &[(u16, fn(Option<u32>) -> Option<u64>)] = &[
+				(1, |v: Option<u32>| v.map(|v| v as u64 + 1)),
+				(2, |v: Option<u32>| v.map(|v| v as u64 + 100)),
+			];
+			Versioned::migrate(2, steps).unwrap();
+
+			// Only the version-1 hop ran; a second `migrate` call with the
+			// version-2 hop's own `Prev` type is needed to reach version 2.
+			assert_eq!(<Versioned as storage::StorageValue<u64>>::get(), Some(12));
+			assert_eq!(Versioned::on_chain_version(), 1);
+		});
+	}
+
+	#[test]
+	fn migrate_picks_lowest_owed_hop_regardless_of_slice_order() {
+		TestExternalities::default().execute_with(|| {
+			unhashed::put(&Versioned::storage_value_final_key(), &11u32);
+
+			// Version 2's hop is listed before version 1's.
+			let steps: &[(u16, fn(Option<u32>) -> Option<u64>)] = &[
+				(2, |v: Option<u32>| v.map(|v| v as u64 + 100)),
+				(1, |v: Option<u32>| v.map(|v| v as u64 + 1)),
+			];
+			Versioned::migrate(2, steps).unwrap();
+
+			assert_eq!(<Versioned as storage::StorageValue<u64>>::get(), Some(12));
+			assert_eq!(Versioned::on_chain_version(), 1);
+		});
+	}
+
+	#[test]
+	fn migrate_halts_on_decode_failure_without_wiping_the_key() {
+		TestExternalities::default().execute_with(|| {
+			// Too short to decode as the `u64` the step below expects.
+			unhashed::put_raw(&Versioned::storage_value_final_key(), &[1, 2, 3]);
+
+			let steps: &[(u16, fn(Option<u64>) -> Option<u64>)] = &[(1, |v| v)];
+			let result = Versioned::migrate(1, steps);
+
+			assert_eq!(result, Err(()));
+			assert_eq!(Versioned::on_chain_version(), 0);
+			assert_eq!(
+				unhashed::get_raw(&Versioned::storage_value_final_key()),
+				Some(sp_std::vec![1, 2, 3]),
+			);
+		});
+	}
+}